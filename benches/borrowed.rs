@@ -0,0 +1,37 @@
+// Compares the time to lex the same input with and without borrowed tokens.
+// Run with `cargo bench`.
+
+extern crate py_lexer;
+
+use py_lexer::lexer::Lexer;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 200;
+
+fn sample()
+   -> String
+{
+   let line = "def f(name, value=0x1F):\n   '''Docs.'''\n   result = name + 'suffix' * 3.5e2  # comment\n   return [result, b'bytes', value]\n\n";
+   line.repeat(500)
+}
+
+fn time<F>(label: &str, f: F)
+   where F : Fn() -> usize
+{
+   let start = Instant::now();
+   let mut count = 0;
+   for _ in 0..ITERATIONS
+   {
+      count += f();
+   }
+   let elapsed = start.elapsed();
+   println!("{}: {:?} per iteration ({} tokens)", label,
+      elapsed / ITERATIONS, count / ITERATIONS as usize);
+}
+
+fn main()
+{
+   let input = sample();
+   time("owned", || Lexer::new(&input).count());
+   time("borrowed", || Lexer::borrowed(&input).count());
+}
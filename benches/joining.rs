@@ -0,0 +1,29 @@
+// Times lexing runs of many adjacent string literals, which are joined into
+// a single token, at increasing lengths so that any superlinear growth in
+// the joining shows.  Run with `cargo bench`.
+
+extern crate py_lexer;
+
+use py_lexer::lexer::Lexer;
+use std::time::Instant;
+
+fn main()
+{
+   for &count in [2500, 5000, 10000, 20000].iter()
+   {
+      let strings = "'abc' ".repeat(count) + "\n";
+      let bytes = "b'abc' ".repeat(count) + "\n";
+      let f_strings = "'abc' f'{x}' ".repeat(count / 2) + "\n";
+      for &(label, input) in
+         [("strings", &strings), ("bytes", &bytes), ("mixed", &f_strings)].iter()
+      {
+         let start = Instant::now();
+         let tokens = Lexer::new(input).count();
+         let owned = start.elapsed();
+         let start = Instant::now();
+         Lexer::borrowed(input).count();
+         println!("{} {}: {:?} owned, {:?} borrowed ({} tokens)", count, label,
+            owned, start.elapsed(), tokens);
+      }
+   }
+}
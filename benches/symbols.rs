@@ -0,0 +1,23 @@
+// Times lexing input dominated by operators and delimiters.
+// Run with `cargo bench`.
+
+extern crate py_lexer;
+
+use py_lexer::lexer::Lexer;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 200;
+
+fn main()
+{
+   let line = "x[i:j] = (a ** 2 // b) << c >>= d != e <= f -> g ... @h\n";
+   let input = line.repeat(1000);
+   let start = Instant::now();
+   let mut count = 0;
+   for _ in 0..ITERATIONS
+   {
+      count += Lexer::new(&input).count();
+   }
+   println!("symbols: {:?} per iteration ({} tokens)",
+      start.elapsed() / ITERATIONS, count / ITERATIONS as usize);
+}
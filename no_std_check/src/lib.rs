@@ -0,0 +1,17 @@
+//! Builds the lexer without the standard library.  Run `cargo build` in this
+//! directory; it fails if the lexer requires std with the `std` feature off.
+
+#![no_std]
+
+extern crate alloc;
+extern crate py_lexer;
+
+use alloc::vec::Vec;
+use py_lexer::lexer::Lexer;
+use py_lexer::tokens::Token;
+
+pub fn tokens(source: &str)
+   -> Vec<Token>
+{
+   Lexer::new(source).filter_map(|(_, token)| token.ok()).collect()
+}
@@ -0,0 +1,151 @@
+//! Detection of the source encoding declaration described by PEP 263.
+//! Apart from Latin-1, which `decode_source` handles, no transcoding is
+//! performed.
+
+use std::borrow::Cow;
+use errors::LexerError;
+use lexer::is_space;
+use prelude::*;
+
+/// Returns the encoding name declared by a `coding[:=]` comment on the
+/// first or second line of the input.  As in Python, the second line is
+/// only considered when the first line is blank or a comment.
+pub fn detect_encoding(input: &str)
+   -> Option<String>
+{
+   for (i, line) in input.lines().take(2).enumerate()
+   {
+      if let Some(name) = declared_encoding(line)
+      {
+         return Some(name.to_owned());
+      }
+      if i == 0 && !is_blank_or_comment(line)
+      {
+         break;
+      }
+   }
+   None
+}
+
+/// Decodes source read as bytes.  Source declaring a Latin-1 encoding is
+/// decoded as such.  Source declaring UTF-8, or no encoding at all, is
+/// decoded as UTF-8, with each invalid sequence replaced by U+FFFD (as by
+/// `String::from_utf8_lossy`), so that lexing can proceed regardless.  Any
+/// other declared encoding is reported as
+/// `LexerError::UnsupportedEncoding` rather than decoded incorrectly.
+pub fn decode_source(bytes: &[u8])
+   -> Result<Cow<'_, str>, LexerError>
+{
+   let decoded = String::from_utf8_lossy(bytes);
+   match detect_encoding(&decoded)
+   {
+      Some(ref name) if is_encoding(name, &["latin-1", "iso-8859-1",
+            "iso-latin-1"]) =>
+         Ok(Cow::Owned(bytes.iter().map(|&b| b as char).collect())),
+      Some(ref name) if !is_encoding(name, &["utf-8", "utf8"]) =>
+         Err(LexerError::UnsupportedEncoding(name.clone())),
+      _ => Ok(decoded),
+   }
+}
+
+// As in Python, names are compared ignoring case and the choice of '_' or
+// '-', and variants (e.g., "latin-1-unix") are accepted.
+fn is_encoding(name: &str, names: &[&str])
+   -> bool
+{
+   let name = name.to_lowercase().replace('_', "-");
+   names.iter().any(|prefix|
+      name == *prefix || name.starts_with(&format!("{}-", prefix)))
+}
+
+// The name in a comment of the form "# ... coding: name" (or "coding=name")
+// on the given line, if any.
+fn declared_encoding(line: &str)
+   -> Option<&str>
+{
+   let rest = line.trim_start_matches(is_space);
+   if !rest.starts_with('#')
+   {
+      return None;
+   }
+   for (i, _) in rest.match_indices("coding")
+   {
+      let declaration = &rest[i + "coding".len()..];
+      if declaration.starts_with([':', '='])
+      {
+         let name =
+            declaration[1..].trim_start_matches([' ', '\t']);
+         let len = name.find(|c: char|
+               !(c.is_alphanumeric() || c == '_' || c == '-' || c == '.'))
+            .unwrap_or(name.len());
+         if len > 0
+         {
+            return Some(&name[..len]);
+         }
+      }
+   }
+   None
+}
+
+fn is_blank_or_comment(line: &str)
+   -> bool
+{
+   let rest = line.trim_start_matches(is_space);
+   rest.is_empty() || rest == "\r" || rest.starts_with('#')
+}
+
+#[cfg(test)]
+mod tests
+{
+   use super::{detect_encoding, decode_source};
+   use std::borrow::Cow;
+   use errors::LexerError;
+
+   #[test]
+   fn test_emacs_style()
+   {
+      let chars = "#!/usr/bin/env python\n# -*- coding: latin-1 -*-\nx = 1\n";
+      assert_eq!(detect_encoding(chars), Some("latin-1".to_owned()));
+   }
+
+   #[test]
+   fn test_bare()
+   {
+      assert_eq!(detect_encoding("# coding: utf-8\n"), Some("utf-8".to_owned()));
+      assert_eq!(detect_encoding("# vim: set fileencoding=utf-8 :\n"), Some("utf-8".to_owned()));
+   }
+
+   #[test]
+   fn test_absent()
+   {
+      assert_eq!(detect_encoding(""), None);
+      assert_eq!(detect_encoding("x = 1\n"), None);
+      assert_eq!(detect_encoding("x = 1\n# coding: utf-8\n"), None);
+      assert_eq!(detect_encoding("#\n#\n# coding: utf-8\n"), None);
+   }
+
+   #[test]
+   fn test_decode_source()
+   {
+      match decode_source(b"x = 'caf\xc3\xa9'\n")
+      {
+         Ok(Cow::Borrowed(s)) => assert_eq!(s, "x = 'caf\u{e9}'\n"),
+         result => panic!("valid UTF-8 not borrowed: {:?}", result),
+      }
+      assert_eq!(decode_source(b"x = 'caf\xe9'\n").unwrap(), "x = 'caf\u{fffd}'\n");
+      assert_eq!(decode_source(b"# coding: UTF_8\n'\xe9'").unwrap(), "# coding: UTF_8\n'\u{fffd}'");
+      assert_eq!(decode_source(b"# coding: latin-1\nx = 'caf\xe9'\n").unwrap(),
+         "# coding: latin-1\nx = 'caf\u{e9}'\n");
+      assert_eq!(decode_source(b"# coding=ISO_8859_1\n'\xff'").unwrap(),
+         "# coding=ISO_8859_1\n'\u{ff}'");
+   }
+
+   #[test]
+   fn test_unsupported_encoding()
+   {
+      assert_eq!(decode_source(b"# coding: cp1252\nx = '\x80'\n"),
+         Err(LexerError::UnsupportedEncoding("cp1252".to_owned())));
+      assert_eq!(decode_source(b"# -*- coding: shift_jis -*-\n"),
+         Err(LexerError::UnsupportedEncoding("shift_jis".to_owned())));
+   }
+}
@@ -1,67 +1,135 @@
+use std::borrow::Cow;
+#[cfg(feature = "std")]
 use std::error;
 use std::fmt;
+#[cfg(feature = "std")]
+use std::io;
+use prelude::*;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum LexerError
 {
-   BadLineContinuation,
-   UnterminatedTripleString,
-   UnterminatedString,
+   BadLineContinuation(Option<char>),    // character following the '\'
+   UnterminatedTripleString(usize, usize), // line and column of the
+   UnterminatedString(usize, usize),       // opening quote
    InvalidCharacter(char),
+   NullByteInSource(usize, usize),       // line and column of the byte
    Dedent,
    HexEscapeShort,
    MalformedUnicodeEscape,
-   MalformedNamedUnicodeEscape,
+   MalformedNamedUnicodeEscape,          // e.g., an empty name
+   NamedEscapeMissingOpenBrace,
+   NamedEscapeMissingCloseBrace,
+   NamedEscapeAtEnd,                     // a bare \N ending the string
+   UnknownEscape(char),                  // the character following the '\'
    UnknownUnicodeName(String),
    BytesNonASCII,
-   MissingDigits,
-   MalformedFloat,
+   MissingDigits(String),                // the prefix lacking digits
+   LeadingZeroInteger(String),
+   MisplacedUnderscore(String),          // the literal through the underscore
    MalformedImaginary,
    InvalidSymbol(String),
+   InvalidStringPrefix(String),
+   TruncatedToken,
+   UnmatchedBracket,
+   MismatchedBracket{expected: char, found: char}, // expected closer
+   LimitExceeded(String),                // the limit exceeded
+   TabInExpression,
+   TrailingWhitespace,
+   BlankLineWhitespace,
+   UnsupportedEncoding(String),          // the declared encoding
    Internal(String),
 }
 
-impl fmt::Display for LexerError
+impl LexerError
 {
-   fn fmt(&self, f: &mut fmt::Formatter)
-      -> fmt::Result
+   /// The message describing the error, as displayed.  Only the messages
+   /// that include details of the error are allocated.
+   pub fn message(&self)
+      -> Cow<'static, str>
    {
       match *self
       {
-         LexerError::BadLineContinuation =>
-            write!(f, "bad line continuation"),
-         LexerError::UnterminatedTripleString =>
-            write!(f, "unterminated triple-quoted string"),
-         LexerError::UnterminatedString =>
-            write!(f, "unterminated string"),
+         LexerError::BadLineContinuation(Some(ref c)) => Cow::Owned(
+            format!("bad line continuation, unexpected {:?} after '\\'", c)),
+         LexerError::BadLineContinuation(None) =>
+            Cow::Borrowed("bad line continuation, unexpected end of input"),
+         LexerError::UnterminatedTripleString(line, column) => Cow::Owned(
+            format!("unterminated triple-quoted string starting at {}:{}",
+               line, column)),
+         LexerError::UnterminatedString(line, column) => Cow::Owned(
+            format!("unterminated string starting at {}:{}", line, column)),
          LexerError::InvalidCharacter(ref c) =>
-            write!(f, "invalid character '{}'", c),
+            Cow::Owned(format!("invalid character '{}'", c)),
+         LexerError::NullByteInSource(line, column) => Cow::Owned(
+            format!("source contains a null byte at {}:{}", line, column)),
          LexerError::Dedent =>
-            write!(f, "misaligned dedent"),
+            Cow::Borrowed("misaligned dedent"),
          LexerError::HexEscapeShort =>
-            write!(f, "missing digits in hex escape"),
+            Cow::Borrowed("missing digits in hex escape"),
          LexerError::MalformedUnicodeEscape =>
-            write!(f, "malformed unicode escape"),
+            Cow::Borrowed("malformed unicode escape"),
          LexerError::MalformedNamedUnicodeEscape =>
-            write!(f, "malformed named unicode escape"),
+            Cow::Borrowed("malformed named unicode escape"),
+         LexerError::NamedEscapeMissingOpenBrace =>
+            Cow::Borrowed("named unicode escape missing '{'"),
+         LexerError::NamedEscapeMissingCloseBrace =>
+            Cow::Borrowed("named unicode escape missing '}'"),
+         LexerError::NamedEscapeAtEnd =>
+            Cow::Borrowed("named unicode escape at end of string"),
+         LexerError::UnknownEscape(ref c) =>
+            Cow::Owned(format!("invalid escape sequence '\\{}'", c)),
          LexerError::UnknownUnicodeName(ref s) =>
-            write!(f, "unknown unicode name '{}'", s),
-         LexerError::MissingDigits =>
-            write!(f, "missing digits"),
+            Cow::Owned(format!("unknown unicode name '{}'", s)),
+         LexerError::MissingDigits(ref s) =>
+            Cow::Owned(format!("missing digits after {}", s)),
          LexerError::BytesNonASCII =>
-            write!(f, "bytes cannot contain non-ASCII characters"),
-         LexerError::MalformedFloat =>
-            write!(f, "malformed floating point number"),
+            Cow::Borrowed("bytes cannot contain non-ASCII characters"),
+         LexerError::LeadingZeroInteger(ref s) =>
+            Cow::Owned(format!("leading zeros in decimal integer literals \
+               are not permitted: '{}'", s)),
+         LexerError::MisplacedUnderscore(ref s) =>
+            Cow::Owned(format!("underscores in numeric literals must \
+               separate digits: '{}'", s)),
          LexerError::MalformedImaginary =>
-            write!(f, "malformed imaginary number"),
+            Cow::Borrowed("malformed imaginary number"),
          LexerError::InvalidSymbol(ref s) =>
-            write!(f, "invalid symbol '{}'", s),
+            Cow::Owned(format!("invalid symbol '{}'", s)),
+         LexerError::InvalidStringPrefix(ref s) =>
+            Cow::Owned(format!("invalid string prefix '{}'", s)),
+         LexerError::TruncatedToken =>
+            Cow::Borrowed("token truncated by end of input"),
+         LexerError::UnmatchedBracket =>
+            Cow::Borrowed("bracket never closed"),
+         LexerError::MismatchedBracket{expected, found} => Cow::Owned(
+            format!("closing '{}' does not match the open bracket, \
+               expected '{}'", found, expected)),
+         LexerError::LimitExceeded(ref s) =>
+            Cow::Owned(format!("{} limit exceeded", s)),
+         LexerError::TabInExpression =>
+            Cow::Borrowed("tab outside of indentation"),
+         LexerError::TrailingWhitespace =>
+            Cow::Borrowed("trailing whitespace"),
+         LexerError::BlankLineWhitespace =>
+            Cow::Borrowed("blank line contains whitespace"),
+         LexerError::UnsupportedEncoding(ref s) =>
+            Cow::Owned(format!("unsupported source encoding '{}'", s)),
          LexerError::Internal(ref s) =>
-            write!(f, "internal error: {}", s),
+            Cow::Owned(format!("internal error: {}", s)),
       }
    }
 }
 
+impl fmt::Display for LexerError
+{
+   fn fmt(&self, f: &mut fmt::Formatter)
+      -> fmt::Result
+   {
+      f.write_str(&self.message())
+   }
+}
+
+#[cfg(feature = "std")]
 impl error::Error for LexerError
 {
    fn description(&self)
@@ -69,24 +137,157 @@ impl error::Error for LexerError
    {
       match *self
       {
-         LexerError::BadLineContinuation => "bad line continuation",
-         LexerError::UnterminatedTripleString =>
+         LexerError::BadLineContinuation(_) => "bad line continuation",
+         LexerError::UnterminatedTripleString(..) =>
             "unterminated triple-quoted string",
-         LexerError::UnterminatedString => "unterminated string",
+         LexerError::UnterminatedString(..) => "unterminated string",
          LexerError::InvalidCharacter(_) => "invalid character",
+         LexerError::NullByteInSource(..) => "source contains a null byte",
          LexerError::Dedent => "misaligned dedent",
          LexerError::HexEscapeShort => "missing digits in hex escape",
          LexerError::MalformedUnicodeEscape => "malformed unicode escape",
          LexerError::MalformedNamedUnicodeEscape =>
             "malformed named unicode escape",
+         LexerError::NamedEscapeMissingOpenBrace =>
+            "named unicode escape missing '{'",
+         LexerError::NamedEscapeMissingCloseBrace =>
+            "named unicode escape missing '}'",
+         LexerError::NamedEscapeAtEnd =>
+            "named unicode escape at end of string",
+         LexerError::UnknownEscape(_) => "invalid escape sequence",
          LexerError::UnknownUnicodeName(_) => "unknown unicode name",
          LexerError::BytesNonASCII =>
             "bytes cannot contain non-ASCII characters",
-         LexerError::MissingDigits => "missing digits",
-         LexerError::MalformedFloat => "malformed floating point number",
+         LexerError::MissingDigits(_) => "missing digits",
+         LexerError::LeadingZeroInteger(_) =>
+            "leading zeros in decimal integer literals are not permitted",
+         LexerError::MisplacedUnderscore(_) =>
+            "underscores in numeric literals must separate digits",
          LexerError::MalformedImaginary => "malformed imaginary number",
          LexerError::InvalidSymbol(_) => "invalid symbol",
+         LexerError::InvalidStringPrefix(_) => "invalid string prefix",
+         LexerError::TruncatedToken => "token truncated by end of input",
+         LexerError::UnmatchedBracket => "bracket never closed",
+         LexerError::MismatchedBracket{..} =>
+            "closing bracket does not match the open bracket",
+         LexerError::LimitExceeded(_) => "limit exceeded",
+         LexerError::TabInExpression => "tab outside of indentation",
+         LexerError::TrailingWhitespace => "trailing whitespace",
+         LexerError::BlankLineWhitespace => "blank line contains whitespace",
+         LexerError::UnsupportedEncoding(_) => "unsupported source encoding",
          LexerError::Internal(_) => "internal error",
       }
    }
 }
+
+/// Lexing errors are reported as invalid data, e.g., for use with `?` in
+/// functions that also read the source.  (Conversion into
+/// `Box<dyn Error>` is provided by the standard library.)
+#[cfg(feature = "std")]
+impl From<LexerError> for io::Error
+{
+   fn from(err: LexerError)
+      -> io::Error
+   {
+      io::Error::new(io::ErrorKind::InvalidData, err)
+   }
+}
+
+#[cfg(test)]
+mod tests
+{
+   use super::LexerError;
+   use std::borrow::Cow;
+   #[cfg(feature = "std")]
+   use std::error::Error;
+   #[cfg(feature = "std")]
+   use std::io;
+   #[cfg(feature = "std")]
+   use lexer::Lexer;
+   #[cfg(feature = "std")]
+   use tokens::Token;
+
+   #[cfg(feature = "std")]
+   fn count_tokens(input: &str)
+      -> Result<usize, LexerError>
+   {
+      let mut count = 0;
+      for (_, token) in Lexer::new(input)
+      {
+         token?;
+         count += 1;
+      }
+      Ok(count)
+   }
+
+   #[cfg(feature = "std")]
+   fn first_token_boxed(input: &str)
+      -> Result<Token, Box<dyn Error>>
+   {
+      count_tokens(input)?;
+      Ok(Lexer::new(input).next().unwrap().1?)
+   }
+
+   #[cfg(feature = "std")]
+   fn first_token_io(input: &str)
+      -> io::Result<Token>
+   {
+      count_tokens(input)?;
+      Ok(Lexer::new(input).next().unwrap().1?)
+   }
+
+   #[cfg(feature = "std")]
+   #[test]
+   fn test_conversions()
+   {
+      assert_eq!(first_token_boxed("x = 1").unwrap(), Token::Identifier("x".to_owned()));
+      assert_eq!(first_token_io("x = 1").unwrap(), Token::Identifier("x".to_owned()));
+
+      let err = first_token_boxed("x = $").unwrap_err();
+      assert_eq!(err.to_string(), "invalid symbol '$'");
+      let err = first_token_io("x = $").unwrap_err();
+      assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+      assert_eq!(err.to_string(), "invalid symbol '$'");
+   }
+
+   #[test]
+   fn test_display()
+   {
+      assert_eq!(LexerError::BadLineContinuation(Some(' ')).to_string(),
+         "bad line continuation, unexpected ' ' after '\\'");
+      assert_eq!(LexerError::BadLineContinuation(None).to_string(),
+         "bad line continuation, unexpected end of input");
+      assert_eq!(LexerError::UnterminatedString(3, 8).to_string(),
+         "unterminated string starting at 3:8");
+      assert_eq!(LexerError::MissingDigits("0x".to_owned()).to_string(),
+         "missing digits after 0x");
+      assert_eq!(LexerError::NamedEscapeMissingCloseBrace.to_string(),
+         "named unicode escape missing '}'");
+      assert_eq!(LexerError::LeadingZeroInteger("0123".to_owned()).to_string(),
+         "leading zeros in decimal integer literals are not permitted: '0123'");
+      assert_eq!(LexerError::MismatchedBracket{expected: ')', found: ']'}.to_string(),
+         "closing ']' does not match the open bracket, expected ')'");
+   }
+
+   #[test]
+   fn test_message()
+   {
+      match LexerError::Dedent.message()
+      {
+         Cow::Borrowed(message) => assert_eq!(message, "misaligned dedent"),
+         Cow::Owned(_) => panic!("constant message allocated"),
+      }
+      match LexerError::BadLineContinuation(None).message()
+      {
+         Cow::Borrowed(_) => (),
+         Cow::Owned(_) => panic!("constant message allocated"),
+      }
+      match LexerError::InvalidSymbol("$".to_owned()).message()
+      {
+         Cow::Owned(message) => assert_eq!(message, "invalid symbol '$'"),
+         Cow::Borrowed(_) => panic!("formatted message borrowed"),
+      }
+      assert_eq!(LexerError::TrailingWhitespace.message(),
+         LexerError::TrailingWhitespace.to_string());
+   }
+}
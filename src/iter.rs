@@ -15,7 +15,7 @@ impl <I> MultiPeekable<I>
    pub fn new(iter: I)
       -> Self
    {
-      MultiPeekable{iter: iter, seen: VecDeque::new(), exhausted: false}
+      MultiPeekable{iter, seen: VecDeque::new(), exhausted: false}
    }
 
    pub fn peek(&mut self)
@@ -48,10 +48,26 @@ impl <I> MultiPeekable<I>
       }
    }
 
+   pub fn get_ref(&self)
+      -> &I
+   {
+      &self.iter
+   }
+
+   /// Provides access to the underlying iterator, e.g., to supply it with
+   /// more input.  Since the iterator may then produce further items, it
+   /// is no longer considered exhausted.
+   pub fn get_mut(&mut self)
+      -> &mut I
+   {
+      self.exhausted = false;
+      &mut self.iter
+   }
+
    fn get_next(&mut self)
       -> Option<I::Item>
    {
-      if self.seen.len() > 0
+      if !self.seen.is_empty()
       {
          self.seen.pop_front()
       }
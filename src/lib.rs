@@ -1,9 +1,38 @@
-#[macro_use(lazy_static)]
-extern crate lazy_static;
-extern crate regex;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(not(any(feature = "std", test)))]
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "std")]
 extern crate unicode_names;
+extern crate unicode_xid;
+#[cfg(feature = "nfkc")]
+extern crate unicode_normalization;
+#[cfg(feature = "bigint")]
+extern crate num_bigint;
 
 pub mod lexer;
 pub mod tokens;
 pub mod errors;
 pub mod iter;
+pub mod span;
+pub mod encoding;
+pub mod options;
+pub mod newline;
+pub mod relex;
+
+// Without std, the parts of it that the lexer uses come from core and alloc.
+#[cfg(not(any(feature = "std", test)))]
+mod std
+{
+   pub use core::*;
+   pub use alloc::{borrow, collections, fmt, string, vec};
+}
+
+// The allocating types that the std prelude would otherwise provide.
+mod prelude
+{
+   pub use std::borrow::ToOwned;
+   pub use std::string::{String, ToString};
+   pub use std::vec::Vec;
+}
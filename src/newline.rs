@@ -0,0 +1,104 @@
+//! Line ending conventions.  The lexer accepts `\r\n`, `\r`, and `\n`
+//! alike, even mixed within one input.
+
+use std::borrow::Cow;
+use prelude::*;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NewlineStyle
+{
+   Lf,
+   CrLf,
+   Cr,
+}
+
+impl NewlineStyle
+{
+   pub fn as_str(&self)
+      -> &'static str
+   {
+      match *self
+      {
+         NewlineStyle::Lf => "\n",
+         NewlineStyle::CrLf => "\r\n",
+         NewlineStyle::Cr => "\r",
+      }
+   }
+}
+
+/// Returns the style of the first line ending in the input, or `None` if
+/// the input is a single line.
+pub fn detect_newline_style(input: &str)
+   -> Option<NewlineStyle>
+{
+   match input.find(['\n', '\r'])
+   {
+      Some(i) if input[i..].starts_with("\r\n") => Some(NewlineStyle::CrLf),
+      Some(i) if input[i..].starts_with('\r') => Some(NewlineStyle::Cr),
+      Some(_) => Some(NewlineStyle::Lf),
+      None => None,
+   }
+}
+
+/// Replaces each `\r\n` and `\r` line ending with `\n`, e.g., so that
+/// spans and columns need not account for two-character line endings.
+/// The input is borrowed unchanged if it has no such line endings.
+pub fn normalize_newlines(input: &str)
+   -> Cow<'_, str>
+{
+   if !input.contains('\r')
+   {
+      return Cow::Borrowed(input);
+   }
+
+   let mut result = String::with_capacity(input.len());
+   let mut chars = input.chars().peekable();
+   while let Some(c) = chars.next()
+   {
+      if c == '\r'
+      {
+         if chars.peek() == Some(&'\n')
+         {
+            chars.next();
+         }
+         result.push('\n');
+      }
+      else
+      {
+         result.push(c);
+      }
+   }
+   Cow::Owned(result)
+}
+
+#[cfg(test)]
+mod tests
+{
+   use super::{NewlineStyle, detect_newline_style, normalize_newlines};
+   use lexer::Lexer;
+   use tokens::Token;
+
+   #[test]
+   fn test_detect()
+   {
+      assert_eq!(detect_newline_style("x = 1"), None);
+      assert_eq!(detect_newline_style("x\ny\r\n"), Some(NewlineStyle::Lf));
+      assert_eq!(detect_newline_style("x\r\ny\n"), Some(NewlineStyle::CrLf));
+      assert_eq!(detect_newline_style("x\ry\r\n"), Some(NewlineStyle::Cr));
+   }
+
+   #[test]
+   fn test_normalize()
+   {
+      let chars = "if x:\r\n   y\n\r\n   z\rw\r";
+      let normalized = normalize_newlines(chars);
+      assert_eq!(normalized, "if x:\n   y\n\n   z\nw\n");
+
+      let before: Vec<_> = Lexer::new(chars).collect();
+      let after: Vec<_> = Lexer::new(&normalized).collect();
+      assert_eq!(before, after);
+      assert_eq!(after[4], (2, Ok(Token::Indent)));
+
+      assert_eq!(normalize_newlines("a\nb\n"), "a\nb\n");
+   }
+}
@@ -0,0 +1,74 @@
+//! Settings that alter how the lexer treats its input.  The defaults lex
+//! Python 3 exactly as `Lexer::new` does.
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PythonVersion
+{
+   Python2,
+   Python3,
+}
+
+/// The treatment of unrecognized escape sequences, such as `\m`, in
+/// (non-raw) string and bytes literals.  Python keeps these as is, but
+/// warns that they are deprecated.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EscapePolicy
+{
+   Preserve,      // keep the backslash and character, as does Python
+   Warn,          // as Preserve, but precede the literal with an error
+   Error,         // report an error in place of the literal
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LexerOptions
+{
+   pub version: PythonVersion,
+   pub line_mode: bool,          // see Lexer::line_mode
+   pub lossless: bool,           // see Lexer::lossless
+   pub comments: bool,           // report comments as Token::Comment, or
+                                 // as Token::TypeComment unless raw
+   pub raw_comments: bool,       // keep the '#' and surrounding whitespace;
+                                 // type comments are then plain comments
+   pub comment_char: char,       // begins a comment, '#' in Python
+   pub tab_errors: bool,         // report tabs between tokens as errors
+   pub whitespace_errors: bool,  // report trailing and blank-line whitespace
+   pub nl_tokens: bool,          // report non-logical line breaks as Token::NL
+   pub verify_balance: bool,     // check that blocks and brackets all close
+   pub escape_policy: EscapePolicy,
+   #[cfg(feature = "nfkc")]
+   pub normalize_identifiers: bool, // NFKC-normalize, as does Python
+   pub limits: LexerLimits,
+}
+
+/// Bounds on the nesting the lexer tracks, for lexing untrusted input.
+/// Exceeding a bound produces `LexerError::LimitExceeded` in place of the
+/// offending `Indent` or opening bracket.  The default is unlimited.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct LexerLimits
+{
+   pub max_indent_depth: Option<usize>,
+   pub max_bracket_depth: Option<usize>,
+}
+
+impl Default for LexerOptions
+{
+   fn default()
+      -> LexerOptions
+   {
+      LexerOptions{version: PythonVersion::Python3,
+         line_mode: false,
+         lossless: false,
+         comments: false,
+         raw_comments: false,
+         comment_char: '#',
+         tab_errors: false,
+         whitespace_errors: false,
+         nl_tokens: false,
+         verify_balance: false,
+         escape_policy: EscapePolicy::Preserve,
+         #[cfg(feature = "nfkc")]
+         normalize_identifiers: false,
+         limits: LexerLimits::default(),
+      }
+   }
+}
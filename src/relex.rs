@@ -0,0 +1,238 @@
+//! Incremental relexing for editors.  After an edit, lexing restarts at the
+//! beginning of the last top-level line preceding the edit and stops once
+//! the new tokens fall back into step with the old tokens following the
+//! edit.  Both token sequences are assumed to come from `Lexer::new`.
+
+use lexer::{Lexer, ResultToken};
+use errors::LexerError;
+use span::Span;
+use tokens::Token;
+use prelude::*;
+
+pub type SpannedToken = (usize, ResultToken, Span);
+
+/// Replacement of the byte range `start..end` of the original input.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TextEdit
+{
+   pub start: usize,
+   pub end: usize,
+   pub replacement: String,
+}
+
+impl TextEdit
+{
+   pub fn new(start: usize, end: usize, replacement: &str)
+      -> TextEdit
+   {
+      TextEdit{start, end, replacement: replacement.to_owned()}
+   }
+
+   /// The original input with the edit applied.
+   pub fn apply(&self, original: &str)
+      -> String
+   {
+      let mut result = original[..self.start].to_owned();
+      result.push_str(&self.replacement);
+      result.push_str(&original[self.end..]);
+      result
+   }
+}
+
+/// Computes the tokens of `input`, the result of applying `edit` to the
+/// input from which `original_tokens` were lexed, reusing the original
+/// tokens before and after the edit where possible.
+pub fn relex_range(original_tokens: &[SpannedToken], edit: &TextEdit,
+   input: &str)
+   -> Vec<SpannedToken>
+{
+   let (kept, restart, restart_line) =
+      restart_point(original_tokens, edit.start);
+   let mut result = original_tokens[..kept].to_vec();
+
+   let resume_points = line_ends(original_tokens, kept);
+   let new_edit_end = edit.start + edit.replacement.len();
+   let mut lexer = Lexer::new_at_line(&input[restart..], restart_line);
+   let mut depth = 0;
+
+   while let Some((line_number, token, span)) = lexer.next_spanned()
+   {
+      let span = Span::new(span.start + restart, span.end + restart);
+      depth = update_depth(depth, &token);
+      let line_end = is_line_end(&token, span) && depth == 0;
+      result.push((line_number, token, span));
+
+      if line_end && span.end >= new_edit_end
+      {
+         // the remaining input is unchanged, as is the lexer state, so the
+         // original tokens from the matching line end on can be reused
+         let old_end = span.end + edit.end - new_edit_end;
+         let resume = resume_points.iter()
+            .find(|&&(i, _)| original_tokens[i].2.end == old_end);
+         if let Some(&(index, old_line)) = resume
+         {
+            let old_start = edit.end;
+            let new_start = new_edit_end;
+            for &(old_line_number, ref token, old_span) in
+               &original_tokens[index + 1..]
+            {
+               result.push((old_line_number + line_number - old_line,
+                  shift_lines(token, old_line, line_number),
+                  Span::new(old_span.start + new_start - old_start,
+                     old_span.end + new_start - old_start)));
+            }
+            break;
+         }
+      }
+   }
+
+   result
+}
+
+// The number of tokens preceding the last top-level line that begins before
+// the given offset, along with the offset and number of that line.  A line
+// ending at the offset is relexed, since it may lack a line break (at the
+// end of the input), in which case the edit extends it.
+fn restart_point(tokens: &[SpannedToken], offset: usize)
+   -> (usize, usize, usize)
+{
+   let mut point = (0, 0, 1);
+   let mut depth = 0;
+
+   for (i, &(line_number, ref token, span)) in tokens.iter().enumerate()
+   {
+      if span.end >= offset
+      {
+         break;
+      }
+      depth = update_depth(depth, token);
+      if is_line_end(token, span) && depth == 0
+      {
+         point = (i + 1, span.end, line_number + 1);
+      }
+   }
+
+   point
+}
+
+// Indices and line numbers of the top-level line ends from the given index.
+fn line_ends(tokens: &[SpannedToken], from: usize)
+   -> Vec<(usize, usize)>
+{
+   let mut ends = vec![];
+   let mut depth = tokens[..from].iter()
+      .fold(0, |depth, (_, token, _)| update_depth(depth, token));
+
+   for (i, &(line_number, ref token, span)) in
+      tokens.iter().enumerate().skip(from)
+   {
+      depth = update_depth(depth, token);
+      if is_line_end(token, span) && depth == 0
+      {
+         ends.push((i, line_number));
+      }
+   }
+
+   ends
+}
+
+// A reused token, with the line numbers reported by an error moved from the
+// original line numbering to the new.
+fn shift_lines(token: &ResultToken, old_line: usize, new_line: usize)
+   -> ResultToken
+{
+   let shift = |line: usize| line + new_line - old_line;
+   match *token
+   {
+      Err(LexerError::UnterminatedString(line, column)) =>
+         Err(LexerError::UnterminatedString(shift(line), column)),
+      Err(LexerError::UnterminatedTripleString(line, column)) =>
+         Err(LexerError::UnterminatedTripleString(shift(line), column)),
+      Err(LexerError::NullByteInSource(line, column)) =>
+         Err(LexerError::NullByteInSource(shift(line), column)),
+      _ => token.clone(),
+   }
+}
+
+fn update_depth(depth: usize, token: &ResultToken)
+   -> usize
+{
+   match *token
+   {
+      Ok(Token::Indent) => depth + 1,
+      Ok(Token::Dedent) | Err(LexerError::Dedent) => depth.saturating_sub(1),
+      _ => depth,
+   }
+}
+
+// A Newline ending a line of the input, as opposed to that supplied at the
+// end of the input.
+fn is_line_end(token: &ResultToken, span: Span)
+   -> bool
+{
+   *token == Ok(Token::Newline) && !span.is_empty()
+}
+
+#[cfg(test)]
+mod tests
+{
+   use super::{relex_range, TextEdit, SpannedToken};
+   use lexer::Lexer;
+
+   fn lex(input: &str)
+      -> Vec<SpannedToken>
+   {
+      let mut l = Lexer::new(input);
+      let mut tokens = vec![];
+      while let Some(token) = l.next_spanned()
+      {
+         tokens.push(token);
+      }
+      tokens
+   }
+
+   fn check(original: &str, edit: TextEdit)
+   {
+      let input = edit.apply(original);
+      assert_eq!(relex_range(&lex(original), &edit, &input), lex(&input),
+         "edit {:?} of {:?}", edit, original);
+   }
+
+   #[test]
+   fn test_relex_range()
+   {
+      let original = "import os\n\ndef f(x):\n   if x:\n      return [1,\n         2]\n   return 3\n\ny = f(0)\nz = '''a\nb'''\n";
+      check(original, TextEdit::new(0, 6, "from"));
+      check(original, TextEdit::new(36, 42, "yield"));
+      check(original, TextEdit::new(44, 45, "(1,\n    "));
+      check(original, TextEdit::new(57, 58, ")"));
+      check(original, TextEdit::new(62, 62, "pass\n   "));
+      check(original, TextEdit::new(62, 62, "pass\n"));
+      check(original, TextEdit::new(76, 77, "(g"));
+      check(original, TextEdit::new(81, 81, "'''"));
+      check(original, TextEdit::new(89, 89, "\r\n"));
+      check(original, TextEdit::new(original.len(), original.len(), "w"));
+      check(original, TextEdit::new(0, original.len(), ""));
+      check("", TextEdit::new(0, 0, "x = 1\n"));
+   }
+
+   #[test]
+   fn test_relex_errors()
+   {
+      check("x = 1 # c", TextEdit::new(9, 9, "omment"));
+      check("x = 1 # c", TextEdit::new(5, 5, " + 2"));
+      check("x = 1  ", TextEdit::new(7, 7, "y"));
+      let original = "x = 1\ny = 2\nz = 3 \0\nw = 'a\nv = '''b\n";
+      check(original, TextEdit::new(0, 0, "u = 0\n"));
+      check(original, TextEdit::new(6, 11, "if y:\n   y = (2,\n3)"));
+      check(original, TextEdit::new(12, 12, "\n\n"));
+      check(original, TextEdit::new(6, 12, ""));
+   }
+
+   #[test]
+   fn test_apply()
+   {
+      assert_eq!(TextEdit::new(4, 5, "22").apply("x = 1\n"), "x = 22\n");
+      assert_eq!(TextEdit::new(0, 0, "# c\n").apply("x\n"), "# c\nx\n");
+   }
+}
@@ -0,0 +1,114 @@
+//! Byte offsets into the original input delimiting the source text of a
+//! token.  Layout tokens that do not correspond to any text (e.g., `Indent`,
+//! `Dedent`, and the `Newline` supplied at the end of input) have an empty
+//! span.
+
+use errors::LexerError;
+use lexer::ResultToken;
+use prelude::*;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span
+{
+   pub start: usize,
+   pub end: usize,
+}
+
+impl Span
+{
+   pub fn new(start: usize, end: usize)
+      -> Span
+   {
+      Span{start, end}
+   }
+
+   pub fn len(&self)
+      -> usize
+   {
+      self.end - self.start
+   }
+
+   pub fn is_empty(&self)
+      -> bool
+   {
+      self.start == self.end
+   }
+}
+
+/// A token positioned in the manner of Python's `tokenize.TokenInfo`.
+/// Positions are (row, column) pairs with rows numbered from 1 and columns
+/// counted in characters from 0.  The `line` holds the physical lines,
+/// including their line terminators, on which the token lies.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TokenInfo
+{
+   pub token: ResultToken,
+   pub start: (usize, usize),
+   pub end: (usize, usize),
+   pub line: String,
+}
+
+/// Verifies that the spans of consecutive tokens are well formed, do not
+/// overlap, and appear in order of their position in the input.  Tokens
+/// with empty spans (e.g., `Indent` and `Dedent`) are not checked against
+/// their neighbors.
+pub fn validate_spans(tokens: &[(usize, ResultToken, Span)])
+   -> Result<(), LexerError>
+{
+   let mut previous_end = 0;
+
+   for (i, &(_, _, span)) in tokens.iter().enumerate()
+   {
+      if span.start > span.end
+      {
+         return Err(LexerError::Internal(
+            format!("token {} has inverted span {:?}", i, span)));
+      }
+      else if !span.is_empty()
+      {
+         if span.start < previous_end
+         {
+            return Err(LexerError::Internal(
+               format!("token {} span {:?} overlaps previous token", i, span)));
+         }
+         previous_end = span.end;
+      }
+   }
+
+   Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+   use super::{Span, validate_spans};
+   use errors::LexerError;
+   use lexer::Lexer;
+   use tokens::Token;
+
+   #[test]
+   fn test_validate_lexed()
+   {
+      let chars = "def f(a, b):\n   return 'x' 'y' + b'z' \\\n      + a  # done\n";
+      let mut l = Lexer::new(chars);
+      let mut tokens = vec![];
+      while let Some(token) = l.next_spanned()
+      {
+         tokens.push(token);
+      }
+      assert_eq!(validate_spans(&tokens), Ok(()));
+   }
+
+   #[test]
+   fn test_validate_overlap()
+   {
+      let tokens = vec![
+         (1, Ok(Token::Identifier("ab".to_owned())), Span::new(0, 2)),
+         (1, Ok(Token::Indent), Span::new(1, 1)),
+         (1, Ok(Token::Identifier("b".to_owned())), Span::new(1, 2)),
+      ];
+      assert_eq!(validate_spans(&tokens),
+         Err(LexerError::Internal(
+            "token 2 span Span { start: 1, end: 2 } overlaps previous token".to_owned())));
+   }
+}
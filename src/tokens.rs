@@ -1,10 +1,16 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
 use errors::LexerError;
+use span::Span;
+use options::PythonVersion;
+use prelude::*;
+#[cfg(feature = "bigint")]
+use num_bigint::BigUint;
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Token
 {
    Newline,
+   NL,                     // only produced when NL tokens are enabled
    Indent,
    Dedent,
    False,
@@ -23,6 +29,7 @@ pub enum Token
    Elif,
    Else,
    Except,
+   Exec,                   // Python 2 only
    Finally,
    For,
    From,
@@ -36,6 +43,7 @@ pub enum Token
    Not,
    Or,
    Pass,
+   Print,                  // Python 2 only
    Raise,
    Return,
    Try,
@@ -74,7 +82,9 @@ pub enum Token
    Ellipsis,
    Semi,
    Arrow,
+   Backtick,               // Python 2 only
    Assign,
+   ColonAssign,
    AssignPlus,
    AssignMinus,
    AssignTimes,
@@ -88,10 +98,9 @@ pub enum Token
    AssignRshift,
    AssignLshift,
    AssignExponent,
-   Quote,
-   DoubleQuote,
    Identifier(String),
    String(String),
+   FormatString(String),   // escapes processed, replacement fields intact
    Bytes(Vec<u8>),
    DecInteger(String),
    BinInteger(String),
@@ -99,6 +108,90 @@ pub enum Token
    HexInteger(String),
    Float(String),
    Imaginary(String),
+   Whitespace(String),     // only produced in lossless mode
+   Comment(String),        // only produced when comments are enabled
+   TypeComment(String),    // the annotation of a PEP 484 "# type:" comment
+}
+
+/// A token that borrows its contents from the input where possible, as
+/// produced by `Lexer::borrowed`.  Tokens without contents are `Fixed`.
+/// Strings and bytes are borrowed unless escapes had to be processed or
+/// adjacent literals joined.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum BorrowedToken<'a>
+{
+   Fixed(Token),
+   Identifier(Cow<'a, str>),     // owned only when normalized
+   String(Cow<'a, str>),
+   FormatString(Cow<'a, str>),
+   Bytes(Cow<'a, [u8]>),
+   DecInteger(&'a str),
+   BinInteger(&'a str),
+   OctInteger(&'a str),
+   HexInteger(&'a str),
+   Float(&'a str),
+   Imaginary(&'a str),
+   Whitespace(&'a str),
+   Comment(&'a str),
+   TypeComment(&'a str),
+}
+
+impl <'a> BorrowedToken<'a>
+{
+   pub fn into_owned(self)
+      -> Token
+   {
+      match self
+      {
+         BorrowedToken::Fixed(token) => token,
+         BorrowedToken::Identifier(s) => Token::Identifier(s.into_owned()),
+         BorrowedToken::String(s) => Token::String(s.into_owned()),
+         BorrowedToken::FormatString(s) =>
+            Token::FormatString(s.into_owned()),
+         BorrowedToken::Bytes(b) => Token::Bytes(b.into_owned()),
+         BorrowedToken::DecInteger(s) => Token::DecInteger(s.to_owned()),
+         BorrowedToken::BinInteger(s) => Token::BinInteger(s.to_owned()),
+         BorrowedToken::OctInteger(s) => Token::OctInteger(s.to_owned()),
+         BorrowedToken::HexInteger(s) => Token::HexInteger(s.to_owned()),
+         BorrowedToken::Float(s) => Token::Float(s.to_owned()),
+         BorrowedToken::Imaginary(s) => Token::Imaginary(s.to_owned()),
+         BorrowedToken::Whitespace(s) => Token::Whitespace(s.to_owned()),
+         BorrowedToken::Comment(s) => Token::Comment(s.to_owned()),
+         BorrowedToken::TypeComment(s) => Token::TypeComment(s.to_owned()),
+      }
+   }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum TokenCategory
+{
+   Keyword,
+   Identifier,
+   NumericLiteral,
+   StringLiteral,
+   Operator,
+   Delimiter,
+   Layout,
+   Comment,
+}
+
+/// The value of a numeric literal.  The value of an imaginary literal is
+/// its (floating point) imaginary part.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NumericValue
+{
+   Integer(u128),
+   Float(f64),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct NumericInfo
+{
+   pub value: Option<NumericValue>,    // None if too large for a u128
+   pub base: u32,
+   pub is_float: bool,                 // has a fraction or exponent
+   pub is_imaginary: bool,
+   pub source_len: usize,
 }
 
 impl Token
@@ -106,20 +199,351 @@ impl Token
    pub fn is_decimal_integer(&self)
       -> bool
    {
-      match self
+      matches!(*self, Token::DecInteger(_))
+   }
+
+   /// The base of an integer literal, or `None` for all other tokens.
+   pub fn base(&self)
+      -> Option<u32>
+   {
+      match *self
       {
-         &Token::DecInteger(_) => true,
-         _ => false,
+         Token::DecInteger(_) => Some(10),
+         Token::HexInteger(_) => Some(16),
+         Token::OctInteger(_) => Some(8),
+         Token::BinInteger(_) => Some(2),
+         _ => None,
+      }
+   }
+
+   /// The digits of an integer literal without its `0x`, `0o`, or `0b`
+   /// prefix, or `None` for all other tokens.
+   pub fn digits_without_prefix(&self)
+      -> Option<&str>
+   {
+      match *self
+      {
+         Token::DecInteger(ref s) => Some(s),
+         Token::HexInteger(ref s) | Token::OctInteger(ref s) |
+            Token::BinInteger(ref s) => Some(&s[2..]),
+         _ => None,
       }
    }
 
    pub fn is_float(&self)
       -> bool
+   {
+      matches!(*self, Token::Float(_))
+   }
+
+   pub fn is_keyword(&self)
+      -> bool
+   {
+      matches!(*self,
+         Token::False | Token::None | Token::True | Token::And |
+            Token::As | Token::Assert | Token::Break | Token::Class |
+            Token::Continue | Token::Def | Token::Del | Token::Elif |
+            Token::Else | Token::Except | Token::Finally | Token::For |
+            Token::From | Token::Global | Token::If | Token::Import |
+            Token::In | Token::Is | Token::Lambda | Token::Nonlocal |
+            Token::Not | Token::Or | Token::Pass | Token::Raise |
+            Token::Return | Token::Try | Token::While | Token::With |
+            Token::Yield | Token::Print | Token::Exec)
+   }
+
+   /// The spelling of a keyword token, or `None` for all other tokens.
+   pub fn keyword_str(&self)
+      -> Option<&'static str>
+   {
+      if self.is_keyword()
+      {
+         fixed_lexeme(self)
+      }
+      else
+      {
+         None
+      }
+   }
+
+   pub fn is_operator(&self)
+      -> bool
+   {
+      matches!(*self,
+         Token::Plus | Token::Minus | Token::Times | Token::Exponent |
+            Token::Divide | Token::DivideFloor | Token::Mod |
+            Token::At | Token::Lshift | Token::Rshift | Token::BitAnd |
+            Token::BitOr | Token::BitXor | Token::BitNot | Token::LT |
+            Token::GT | Token::LE | Token::GE | Token::EQ | Token::NE |
+            Token::Assign | Token::ColonAssign |
+            Token::AssignPlus | Token::AssignMinus |
+            Token::AssignTimes | Token::AssignDivide |
+            Token::AssignDivideFloor | Token::AssignMod |
+            Token::AssignAt | Token::AssignBitAnd | Token::AssignBitOr |
+            Token::AssignBitXor | Token::AssignRshift |
+            Token::AssignLshift | Token::AssignExponent)
+   }
+
+   pub fn is_delimiter(&self)
+      -> bool
+   {
+      matches!(*self,
+         Token::Lparen | Token::Rparen | Token::Lbracket |
+            Token::Rbracket | Token::Lbrace | Token::Rbrace |
+            Token::Comma | Token::Colon | Token::Dot | Token::Ellipsis |
+            Token::Semi | Token::Arrow | Token::Backtick)
+   }
+
+   pub fn is_literal(&self)
+      -> bool
+   {
+      matches!(*self,
+         Token::String(_) | Token::FormatString(_) | Token::Bytes(_) |
+            Token::DecInteger(_) | Token::BinInteger(_) |
+            Token::OctInteger(_) | Token::HexInteger(_) |
+            Token::Float(_) | Token::Imaginary(_))
+   }
+
+   /// Whether the token is `@`.  The lexer cannot distinguish a decorator
+   /// marker from matrix multiplication; that is left to the parser, which
+   /// must likewise interpret a `/` in a parameter list as the end of the
+   /// positional-only parameters.
+   pub fn is_at(&self)
+      -> bool
+   {
+      *self == Token::At
+   }
+
+   /// A number identifying the variant of the token, ignoring any data it
+   /// carries.  Numbers are fixed once assigned, so they may be used to
+   /// sort or bucket tokens deterministically; new variants receive new
+   /// numbers.
+   pub fn discriminant(&self)
+      -> u32
+   {
+      match *self
+      {
+         Token::Newline => 0,
+         Token::Indent => 1,
+         Token::Dedent => 2,
+         Token::False => 3,
+         Token::None => 4,
+         Token::True => 5,
+         Token::And => 6,
+         Token::As => 7,
+         Token::Assert => 8,
+         Token::Break => 9,
+         Token::Class => 10,
+         Token::Continue => 11,
+         Token::Def => 12,
+         Token::Del => 13,
+         Token::Elif => 14,
+         Token::Else => 15,
+         Token::Except => 16,
+         Token::Finally => 17,
+         Token::For => 18,
+         Token::From => 19,
+         Token::Global => 20,
+         Token::If => 21,
+         Token::Import => 22,
+         Token::In => 23,
+         Token::Is => 24,
+         Token::Lambda => 25,
+         Token::Nonlocal => 26,
+         Token::Not => 27,
+         Token::Or => 28,
+         Token::Pass => 29,
+         Token::Raise => 30,
+         Token::Return => 31,
+         Token::Try => 32,
+         Token::While => 33,
+         Token::With => 34,
+         Token::Yield => 35,
+         Token::Plus => 36,
+         Token::Minus => 37,
+         Token::Times => 38,
+         Token::Exponent => 39,
+         Token::Divide => 40,
+         Token::DivideFloor => 41,
+         Token::Mod => 42,
+         Token::At => 43,
+         Token::Lshift => 44,
+         Token::Rshift => 45,
+         Token::BitAnd => 46,
+         Token::BitOr => 47,
+         Token::BitXor => 48,
+         Token::BitNot => 49,
+         Token::LT => 50,
+         Token::GT => 51,
+         Token::LE => 52,
+         Token::GE => 53,
+         Token::EQ => 54,
+         Token::NE => 55,
+         Token::Lparen => 56,
+         Token::Rparen => 57,
+         Token::Lbracket => 58,
+         Token::Rbracket => 59,
+         Token::Lbrace => 60,
+         Token::Rbrace => 61,
+         Token::Comma => 62,
+         Token::Colon => 63,
+         Token::Dot => 64,
+         Token::Ellipsis => 65,
+         Token::Semi => 66,
+         Token::Arrow => 67,
+         Token::Assign => 68,
+         Token::ColonAssign => 69,
+         Token::AssignPlus => 70,
+         Token::AssignMinus => 71,
+         Token::AssignTimes => 72,
+         Token::AssignDivide => 73,
+         Token::AssignDivideFloor => 74,
+         Token::AssignMod => 75,
+         Token::AssignAt => 76,
+         Token::AssignBitAnd => 77,
+         Token::AssignBitOr => 78,
+         Token::AssignBitXor => 79,
+         Token::AssignRshift => 80,
+         Token::AssignLshift => 81,
+         Token::AssignExponent => 82,
+         Token::Identifier(_) => 83,
+         Token::String(_) => 84,
+         Token::FormatString(_) => 85,
+         Token::Bytes(_) => 86,
+         Token::DecInteger(_) => 87,
+         Token::BinInteger(_) => 88,
+         Token::OctInteger(_) => 89,
+         Token::HexInteger(_) => 90,
+         Token::Float(_) => 91,
+         Token::Imaginary(_) => 92,
+         Token::Whitespace(_) => 93,
+         Token::Comment(_) => 94,
+         Token::Backtick => 95,
+         Token::Print => 96,
+         Token::Exec => 97,
+         Token::NL => 98,
+         Token::TypeComment(_) => 99,
+      }
+   }
+
+   pub fn category(&self)
+      -> TokenCategory
    {
       match self
       {
-         &Token::Float(_) => true,
-         _ => false,
+         &Token::Newline | &Token::NL | &Token::Indent | &Token::Dedent |
+            &Token::Whitespace(_) => TokenCategory::Layout,
+         &Token::Comment(_) | &Token::TypeComment(_) =>
+            TokenCategory::Comment,
+         &Token::Identifier(_) => TokenCategory::Identifier,
+         &Token::String(_) | &Token::FormatString(_) | &Token::Bytes(_) =>
+            TokenCategory::StringLiteral,
+         token if token.is_literal() => TokenCategory::NumericLiteral,
+         token if token.is_keyword() => TokenCategory::Keyword,
+         token if token.is_operator() => TokenCategory::Operator,
+         _ => TokenCategory::Delimiter,
+      }
+   }
+
+   /// Returns the Pygments token type path used to highlight this token.
+   pub fn pygments_token_type(&self)
+      -> &'static str
+   {
+      match self
+      {
+         &Token::True | &Token::False | &Token::None =>
+            "Token.Keyword.Constant",
+         &Token::And | &Token::Or | &Token::Not | &Token::In | &Token::Is =>
+            "Token.Operator.Word",
+         &Token::Identifier(_) => "Token.Name",
+         &Token::String(_) | &Token::Bytes(_) => "Token.Literal.String",
+         &Token::FormatString(_) => "Token.Literal.String.Interpol",
+         &Token::DecInteger(_) => "Token.Literal.Number.Integer",
+         &Token::BinInteger(_) => "Token.Literal.Number.Bin",
+         &Token::OctInteger(_) => "Token.Literal.Number.Oct",
+         &Token::HexInteger(_) => "Token.Literal.Number.Hex",
+         &Token::Float(_) => "Token.Literal.Number.Float",
+         &Token::Imaginary(_) => "Token.Literal.Number",
+         &Token::Newline | &Token::NL | &Token::Indent | &Token::Dedent =>
+            "Token.Text",
+         &Token::Whitespace(_) => "Token.Text.Whitespace",
+         &Token::Comment(_) | &Token::TypeComment(_) =>
+            "Token.Comment.Single",
+         token if token.is_keyword() => "Token.Keyword",
+         token if token.is_operator() => "Token.Operator",
+         _ => "Token.Punctuation",
+      }
+   }
+
+   /// Decodes a numeric literal token, returning `None` for all other
+   /// tokens.
+   pub fn numeric_info(&self)
+      -> Option<NumericInfo>
+   {
+      let (lexeme, base, is_imaginary) =
+         match *self
+         {
+            Token::DecInteger(ref s) | Token::Float(ref s) => (s, 10, false),
+            Token::BinInteger(ref s) => (s, 2, false),
+            Token::OctInteger(ref s) => (s, 8, false),
+            Token::HexInteger(ref s) => (s, 16, false),
+            Token::Imaginary(ref s) => (s, 10, true),
+            _ => return None,
+         };
+      let s =
+         if is_imaginary { &lexeme[..lexeme.len() - 1] }
+         else if base != 10 { &lexeme[2..] }
+         else { &lexeme[..] };
+      let s = &s.replace('_', "");
+      let is_float = self.is_float() ||
+         (is_imaginary && s.contains(['.', 'e', 'E']));
+
+      let value =
+         if is_float || is_imaginary
+         {
+            s.parse::<f64>().ok().map(NumericValue::Float)
+         }
+         else
+         {
+            // ignore any Python 2 long integer suffix
+            let digits = s.trim_end_matches(['l', 'L']);
+            u128::from_str_radix(digits, base).ok().map(NumericValue::Integer)
+         };
+
+      Some(NumericInfo{value,
+         base,
+         is_float,
+         is_imaginary,
+         source_len: lexeme.len(),
+      })
+   }
+
+   /// Decodes an integer literal of any size, returning `None` for all
+   /// other tokens.
+   #[cfg(feature = "bigint")]
+   pub fn as_biguint(&self)
+      -> Option<BigUint>
+   {
+      let (digits, base) =
+         match *self
+         {
+            Token::DecInteger(ref s) => (&s[..], 10),
+            Token::BinInteger(ref s) => (&s[2..], 2),
+            Token::OctInteger(ref s) => (&s[2..], 8),
+            Token::HexInteger(ref s) => (&s[2..], 16),
+            _ => return None,
+         };
+      match self.numeric_info().and_then(|info| info.value)
+      {
+         Some(NumericValue::Integer(n)) =>
+            Some(BigUint::new(vec![n as u32, (n >> 32) as u32,
+               (n >> 64) as u32, (n >> 96) as u32])),
+         _ =>
+         {
+            // too large for a u128
+            let digits = digits.trim_end_matches(['l', 'L'])
+               .replace('_', "");
+            BigUint::parse_bytes(digits.as_bytes(), base)
+         },
       }
    }
 
@@ -128,265 +552,763 @@ impl Token
    {
       match self
       {
-         Token::Identifier(s) | Token::String(s) |
+         Token::Identifier(s) | Token::String(s) | Token::FormatString(s) |
             Token::DecInteger(s) | Token::BinInteger(s) |
             Token::OctInteger(s) | Token::HexInteger(s) |
-            Token::Float(s) | Token::Imaginary(s) => s,
+            Token::Float(s) | Token::Imaginary(s) |
+            Token::Whitespace(s) | Token::Comment(s) |
+            Token::TypeComment(s) => s,
          Token::Bytes(s) => String::from_utf8(s).unwrap(),
          _ =>
          {
-            match LEXEMES.get(&self)
+            match fixed_lexeme(&self)
             {
-               Some(&s) => s.to_owned(),
+               Some(s) => s.to_owned(),
                None => unreachable!(),
             }
          }
       }
    }
 
+   /// The text of an identifier, string, or numeric literal, or `None` for
+   /// all other tokens.  Unlike `lexeme`, this borrows the token.
+   pub fn as_str_value(&self)
+      -> Option<&str>
+   {
+      match self
+      {
+         &Token::Identifier(ref s) | &Token::String(ref s) |
+            &Token::FormatString(ref s) | &Token::DecInteger(ref s) |
+            &Token::BinInteger(ref s) | &Token::OctInteger(ref s) |
+            &Token::HexInteger(ref s) | &Token::Float(ref s) |
+            &Token::Imaginary(ref s) => Some(s),
+         _ => None,
+      }
+   }
+
+   /// The exact text of the token in the input from which it was lexed,
+   /// given the span reported with the token.  Unlike `lexeme`, this keeps
+   /// string prefixes, quotes, and escapes as written.
+   pub fn source_text<'a>(&self, original: &'a str, span: Span)
+      -> &'a str
+   {
+      &original[span.start..span.end]
+   }
+
    pub fn with_equal(&self)
       -> Self
    {
-      match self
+      match *self
       {
-         &Token::Plus => Token::AssignPlus,
-         &Token::Minus => Token::AssignMinus,
-         &Token::Times => Token::AssignTimes,
-         &Token::Exponent => Token::AssignExponent,
-         &Token::Divide => Token::AssignDivide,
-         &Token::DivideFloor => Token::AssignDivideFloor,
-         &Token::BitAnd => Token::AssignBitAnd,
-         &Token::BitOr => Token::AssignBitOr,
-         &Token::BitXor => Token::AssignBitXor,
-         &Token::Mod => Token::AssignMod,
-         &Token::At => Token::AssignAt,
-         &Token::Assign => Token::EQ,
-         &Token::LT => Token::LE,
-         &Token::Lshift => Token::AssignLshift,
-         &Token::GT => Token::GE,
-         &Token::Rshift => Token::AssignRshift,
+         Token::Plus => Token::AssignPlus,
+         Token::Minus => Token::AssignMinus,
+         Token::Times => Token::AssignTimes,
+         Token::Exponent => Token::AssignExponent,
+         Token::Divide => Token::AssignDivide,
+         Token::DivideFloor => Token::AssignDivideFloor,
+         Token::BitAnd => Token::AssignBitAnd,
+         Token::BitOr => Token::AssignBitOr,
+         Token::BitXor => Token::AssignBitXor,
+         Token::Mod => Token::AssignMod,
+         Token::At => Token::AssignAt,
+         Token::Assign => Token::EQ,
+         Token::Colon => Token::ColonAssign,
+         Token::LT => Token::LE,
+         Token::Lshift => Token::AssignLshift,
+         Token::GT => Token::GE,
+         Token::Rshift => Token::AssignRshift,
          _ => self.clone()
       }
    }
+
+   pub fn is_augmented_assign(&self)
+      -> bool
+   {
+      self.base_operator().is_some()
+   }
+
+   /// The binary operator applied by an augmented assignment (e.g., `Plus`
+   /// for `AssignPlus`), or `None` for any other token.
+   pub fn base_operator(&self)
+      -> Option<Token>
+   {
+      match *self
+      {
+         Token::AssignPlus => Some(Token::Plus),
+         Token::AssignMinus => Some(Token::Minus),
+         Token::AssignTimes => Some(Token::Times),
+         Token::AssignExponent => Some(Token::Exponent),
+         Token::AssignDivide => Some(Token::Divide),
+         Token::AssignDivideFloor => Some(Token::DivideFloor),
+         Token::AssignBitAnd => Some(Token::BitAnd),
+         Token::AssignBitOr => Some(Token::BitOr),
+         Token::AssignBitXor => Some(Token::BitXor),
+         Token::AssignMod => Some(Token::Mod),
+         Token::AssignAt => Some(Token::At),
+         Token::AssignLshift => Some(Token::Lshift),
+         Token::AssignRshift => Some(Token::Rshift),
+         _ => None,
+      }
+   }
+}
+
+/// An identifier or string equals the text of its name or value, e.g.,
+/// `token == "self"`.  No other token equals any text.
+impl PartialEq<str> for Token
+{
+   fn eq(&self, other: &str)
+      -> bool
+   {
+      match self
+      {
+         &Token::Identifier(ref s) | &Token::String(ref s) => s == other,
+         _ => false,
+      }
+   }
 }
 
-pub fn keyword_lookup(token_str: &str)
+impl <'a> PartialEq<&'a str> for Token
+{
+   fn eq(&self, other: &&'a str)
+      -> bool
+   {
+      *self == **other
+   }
+}
+
+/// The keyword spelled by the given text in the given version of Python,
+/// or an identifier if the text is not a keyword.
+pub fn keyword_lookup(token_str: &str, version: PythonVersion)
    -> Token
 {
-   match KEYWORDS.get(token_str)
+   borrowed_keyword_lookup(token_str, version).into_owned()
+}
+
+/// As `keyword_lookup`, but an identifier borrows the given text.
+pub fn borrowed_keyword_lookup(token_str: &str, version: PythonVersion)
+   -> BorrowedToken<'_>
+{
+   let keywords =
+      match version
+      {
+         PythonVersion::Python2 => KEYWORDS_PY2,
+         PythonVersion::Python3 => KEYWORDS_PY3,
+      };
+   match table_lookup(keywords, token_str)
    {
-      Some(token) => token.clone(),
-      None => Token::Identifier(token_str.to_owned()),
+      Some(token) => BorrowedToken::Fixed(token.clone()),
+      None => BorrowedToken::Identifier(Cow::Borrowed(token_str)),
    }
 }
 
+/// Whether the given text is a Python 3 keyword.
+pub fn is_keyword(token_str: &str)
+   -> bool
+{
+   table_lookup(KEYWORDS_PY3, token_str).is_some()
+}
+
+/// The operator or delimiter token for `token_str`, or
+/// `LexerError::InvalidSymbol` if there is none.
 pub fn symbol_lookup(token_str: &str)
    -> Result<Token, LexerError>
 {
-   match SYMBOLS.get(token_str)
+   match table_lookup(SYMBOLS, token_str)
    {
       Some(token) => Ok(token.clone()),
       None => Err(LexerError::InvalidSymbol(token_str.to_owned())),
    }
 }
 
-lazy_static!
+// The token spelled `key` in one of the tables below, which are sorted by
+// spelling.
+fn table_lookup(table: &'static [(&'static str, Token)], key: &str)
+   -> Option<&'static Token>
 {
-   static ref KEYWORDS : HashMap<&'static str, Token> = initialize_keywords();
-   static ref LEXEMES : HashMap<Token, &'static str> = initialize_lexemes();
-   static ref SYMBOLS : HashMap<&'static str, Token> = initialize_symbols();
+   table.binary_search_by(|entry| entry.0.cmp(key)).ok()
+      .map(|index| &table[index].1)
 }
 
-fn initialize_keywords()
-   -> HashMap<&'static str, Token>
-{
-   let mut keywords = HashMap::new();
-
-   keywords.insert("False", Token::False);
-   keywords.insert("None", Token::None);
-   keywords.insert("True", Token::True);
-   keywords.insert("and", Token::And);
-   keywords.insert("as", Token::As);
-   keywords.insert("assert", Token::Assert);
-   //keywords.insert("async", Token::Async);
-   //keywords.insert("await", Token::Await);
-   keywords.insert("break", Token::Break);
-   keywords.insert("class", Token::Class);
-   keywords.insert("continue", Token::Continue);
-   keywords.insert("def", Token::Def);
-   keywords.insert("del", Token::Del);
-   keywords.insert("elif", Token::Elif);
-   keywords.insert("else", Token::Else);
-   keywords.insert("except", Token::Except);
-   keywords.insert("finally", Token::Finally);
-   keywords.insert("for", Token::For);
-   keywords.insert("from", Token::From);
-   keywords.insert("global", Token::Global);
-   keywords.insert("if", Token::If);
-   keywords.insert("import", Token::Import);
-   keywords.insert("in", Token::In);
-   keywords.insert("is", Token::Is);
-   keywords.insert("lambda", Token::Lambda);
-   keywords.insert("nonlocal", Token::Nonlocal);
-   keywords.insert("not", Token::Not);
-   keywords.insert("or", Token::Or);
-   keywords.insert("pass", Token::Pass);
-   keywords.insert("raise", Token::Raise);
-   keywords.insert("return", Token::Return);
-   keywords.insert("try", Token::Try);
-   keywords.insert("while", Token::While);
-   keywords.insert("with", Token::With);
-   keywords.insert("yield", Token::Yield);
-
-   keywords
-}
+static KEYWORDS_PY2: &[(&str, Token)] = &[
+   ("and", Token::And),
+   ("as", Token::As),
+   ("assert", Token::Assert),
+   ("break", Token::Break),
+   ("class", Token::Class),
+   ("continue", Token::Continue),
+   ("def", Token::Def),
+   ("del", Token::Del),
+   ("elif", Token::Elif),
+   ("else", Token::Else),
+   ("except", Token::Except),
+   ("exec", Token::Exec),
+   ("finally", Token::Finally),
+   ("for", Token::For),
+   ("from", Token::From),
+   ("global", Token::Global),
+   ("if", Token::If),
+   ("import", Token::Import),
+   ("in", Token::In),
+   ("is", Token::Is),
+   ("lambda", Token::Lambda),
+   ("not", Token::Not),
+   ("or", Token::Or),
+   ("pass", Token::Pass),
+   ("print", Token::Print),
+   ("raise", Token::Raise),
+   ("return", Token::Return),
+   ("try", Token::Try),
+   ("while", Token::While),
+   ("with", Token::With),
+   ("yield", Token::Yield),
+];
 
-fn initialize_lexemes()
-   -> HashMap<Token, &'static str>
+static KEYWORDS_PY3: &[(&str, Token)] = &[
+   ("False", Token::False),
+   ("None", Token::None),
+   ("True", Token::True),
+   ("and", Token::And),
+   ("as", Token::As),
+   ("assert", Token::Assert),
+   //("async", Token::Async),
+   //("await", Token::Await),
+   ("break", Token::Break),
+   ("class", Token::Class),
+   ("continue", Token::Continue),
+   ("def", Token::Def),
+   ("del", Token::Del),
+   ("elif", Token::Elif),
+   ("else", Token::Else),
+   ("except", Token::Except),
+   ("finally", Token::Finally),
+   ("for", Token::For),
+   ("from", Token::From),
+   ("global", Token::Global),
+   ("if", Token::If),
+   ("import", Token::Import),
+   ("in", Token::In),
+   ("is", Token::Is),
+   ("lambda", Token::Lambda),
+   ("nonlocal", Token::Nonlocal),
+   ("not", Token::Not),
+   ("or", Token::Or),
+   ("pass", Token::Pass),
+   ("raise", Token::Raise),
+   ("return", Token::Return),
+   ("try", Token::Try),
+   ("while", Token::While),
+   ("with", Token::With),
+   ("yield", Token::Yield),
+];
+
+// The spelling of a token without contents.
+fn fixed_lexeme(token: &Token)
+   -> Option<&'static str>
 {
-   let mut lexemes = HashMap::new();
-
-   lexemes.insert(Token::Newline, "\n");
-   lexemes.insert(Token::Indent, "INDENT");
-   lexemes.insert(Token::Dedent, "DEDENT");
-   lexemes.insert(Token::False, "False");
-   lexemes.insert(Token::None, "None");
-   lexemes.insert(Token::True, "True");
-   lexemes.insert(Token::And, "and");
-   lexemes.insert(Token::As, "as");
-   lexemes.insert(Token::Assert, "assert");
-   //lexemes.insert(Token::Async, "async");
-   //lexemes.insert(Token::Await, "await");
-   lexemes.insert(Token::Break, "break");
-   lexemes.insert(Token::Class, "class");
-   lexemes.insert(Token::Continue, "continue");
-   lexemes.insert(Token::Def, "def");
-   lexemes.insert(Token::Del, "del");
-   lexemes.insert(Token::Elif, "elif");
-   lexemes.insert(Token::Else, "else");
-   lexemes.insert(Token::Except, "except");
-   lexemes.insert(Token::Finally, "finally");
-   lexemes.insert(Token::For, "for");
-   lexemes.insert(Token::From, "from");
-   lexemes.insert(Token::Global, "global");
-   lexemes.insert(Token::If, "if");
-   lexemes.insert(Token::Import, "import");
-   lexemes.insert(Token::In, "in");
-   lexemes.insert(Token::Is, "is");
-   lexemes.insert(Token::Lambda, "lambda");
-   lexemes.insert(Token::Nonlocal, "nonlocal");
-   lexemes.insert(Token::Not, "not");
-   lexemes.insert(Token::Or, "or");
-   lexemes.insert(Token::Pass, "pass");
-   lexemes.insert(Token::Raise, "raise");
-   lexemes.insert(Token::Return, "return");
-   lexemes.insert(Token::Try, "try");
-   lexemes.insert(Token::While, "while");
-   lexemes.insert(Token::With, "with");
-   lexemes.insert(Token::Yield, "yield");
-   lexemes.insert(Token::Plus, "+");
-   lexemes.insert(Token::Minus, "-");
-   lexemes.insert(Token::Times, "*");
-   lexemes.insert(Token::Exponent, "**");
-   lexemes.insert(Token::Divide, "/");
-   lexemes.insert(Token::DivideFloor, "//");
-   lexemes.insert(Token::Mod, "%");
-   lexemes.insert(Token::At, "@");
-   lexemes.insert(Token::Lshift, "<<");
-   lexemes.insert(Token::Rshift, ">>");
-   lexemes.insert(Token::BitAnd, "&");
-   lexemes.insert(Token::BitOr, "|");
-   lexemes.insert(Token::BitXor, "^");
-   lexemes.insert(Token::BitNot, "~");
-   lexemes.insert(Token::LT, "<");
-   lexemes.insert(Token::GT, ">");
-   lexemes.insert(Token::LE, "<=");
-   lexemes.insert(Token::GE, ">=");
-   lexemes.insert(Token::EQ, "==");
-   lexemes.insert(Token::NE, "!=");
-   lexemes.insert(Token::Lparen, "(");
-   lexemes.insert(Token::Rparen, ")");
-   lexemes.insert(Token::Lbracket, "[");
-   lexemes.insert(Token::Rbracket, "]");
-   lexemes.insert(Token::Lbrace, "{");
-   lexemes.insert(Token::Rbrace, "}");
-   lexemes.insert(Token::Comma, ",");
-   lexemes.insert(Token::Colon, ":");
-   lexemes.insert(Token::Dot, ".");
-   lexemes.insert(Token::Ellipsis, "...");
-   lexemes.insert(Token::Semi, ";");
-   lexemes.insert(Token::Arrow, "->");
-   lexemes.insert(Token::Assign, "=");
-   lexemes.insert(Token::AssignPlus, "+=");
-   lexemes.insert(Token::AssignMinus, "-=");
-   lexemes.insert(Token::AssignTimes, "*=");
-   lexemes.insert(Token::AssignDivide, "/=");
-   lexemes.insert(Token::AssignDivideFloor, "//=");
-   lexemes.insert(Token::AssignMod, "%");
-   lexemes.insert(Token::AssignAt, "@=");
-   lexemes.insert(Token::AssignBitAnd, "&=");
-   lexemes.insert(Token::AssignBitOr, "|=");
-   lexemes.insert(Token::AssignBitXor, "^=");
-   lexemes.insert(Token::AssignRshift, ">>=");
-   lexemes.insert(Token::AssignLshift, "<<=");
-   lexemes.insert(Token::AssignExponent, "**=");
-   lexemes.insert(Token::Quote, "'");
-   lexemes.insert(Token::DoubleQuote, "\"");
-
-   lexemes
+   match *token
+   {
+      Token::Newline => Some("\n"),
+      Token::NL => Some("\n"),
+      Token::Indent => Some("INDENT"),
+      Token::Dedent => Some("DEDENT"),
+      Token::False => Some("False"),
+      Token::None => Some("None"),
+      Token::True => Some("True"),
+      Token::And => Some("and"),
+      Token::As => Some("as"),
+      Token::Assert => Some("assert"),
+      //Token::Async => Some("async"),
+      //Token::Await => Some("await"),
+      Token::Break => Some("break"),
+      Token::Class => Some("class"),
+      Token::Continue => Some("continue"),
+      Token::Def => Some("def"),
+      Token::Del => Some("del"),
+      Token::Exec => Some("exec"),
+      Token::Print => Some("print"),
+      Token::Elif => Some("elif"),
+      Token::Else => Some("else"),
+      Token::Except => Some("except"),
+      Token::Finally => Some("finally"),
+      Token::For => Some("for"),
+      Token::From => Some("from"),
+      Token::Global => Some("global"),
+      Token::If => Some("if"),
+      Token::Import => Some("import"),
+      Token::In => Some("in"),
+      Token::Is => Some("is"),
+      Token::Lambda => Some("lambda"),
+      Token::Nonlocal => Some("nonlocal"),
+      Token::Not => Some("not"),
+      Token::Or => Some("or"),
+      Token::Pass => Some("pass"),
+      Token::Raise => Some("raise"),
+      Token::Return => Some("return"),
+      Token::Try => Some("try"),
+      Token::While => Some("while"),
+      Token::With => Some("with"),
+      Token::Yield => Some("yield"),
+      Token::Plus => Some("+"),
+      Token::Minus => Some("-"),
+      Token::Times => Some("*"),
+      Token::Exponent => Some("**"),
+      Token::Divide => Some("/"),
+      Token::DivideFloor => Some("//"),
+      Token::Mod => Some("%"),
+      Token::At => Some("@"),
+      Token::Lshift => Some("<<"),
+      Token::Rshift => Some(">>"),
+      Token::BitAnd => Some("&"),
+      Token::BitOr => Some("|"),
+      Token::BitXor => Some("^"),
+      Token::BitNot => Some("~"),
+      Token::LT => Some("<"),
+      Token::GT => Some(">"),
+      Token::LE => Some("<="),
+      Token::GE => Some(">="),
+      Token::EQ => Some("=="),
+      Token::NE => Some("!="),
+      Token::Lparen => Some("("),
+      Token::Rparen => Some(")"),
+      Token::Lbracket => Some("["),
+      Token::Rbracket => Some("]"),
+      Token::Lbrace => Some("{"),
+      Token::Rbrace => Some("}"),
+      Token::Comma => Some(","),
+      Token::Colon => Some(":"),
+      Token::Dot => Some("."),
+      Token::Ellipsis => Some("..."),
+      Token::Semi => Some(";"),
+      Token::Arrow => Some("->"),
+      Token::Backtick => Some("`"),
+      Token::Assign => Some("="),
+      Token::ColonAssign => Some(":="),
+      Token::AssignPlus => Some("+="),
+      Token::AssignMinus => Some("-="),
+      Token::AssignTimes => Some("*="),
+      Token::AssignDivide => Some("/="),
+      Token::AssignDivideFloor => Some("//="),
+      Token::AssignMod => Some("%="),
+      Token::AssignAt => Some("@="),
+      Token::AssignBitAnd => Some("&="),
+      Token::AssignBitOr => Some("|="),
+      Token::AssignBitXor => Some("^="),
+      Token::AssignRshift => Some(">>="),
+      Token::AssignLshift => Some("<<="),
+      Token::AssignExponent => Some("**="),
+      _ => None,
+   }
 }
 
-fn initialize_symbols()
-   -> HashMap<&'static str, Token>
+static SYMBOLS: &[(&str, Token)] = &[
+   ("!=", Token::NE),
+   ("%", Token::Mod),
+   ("%=", Token::AssignMod),
+   ("&", Token::BitAnd),
+   ("&=", Token::AssignBitAnd),
+   ("(", Token::Lparen),
+   (")", Token::Rparen),
+   ("*", Token::Times),
+   ("**", Token::Exponent),
+   ("**=", Token::AssignExponent),
+   ("*=", Token::AssignTimes),
+   ("+", Token::Plus),
+   ("+=", Token::AssignPlus),
+   (",", Token::Comma),
+   ("-", Token::Minus),
+   ("-=", Token::AssignMinus),
+   ("->", Token::Arrow),
+   (".", Token::Dot),
+   ("...", Token::Ellipsis),
+   ("/", Token::Divide),
+   ("//", Token::DivideFloor),
+   ("//=", Token::AssignDivideFloor),
+   ("/=", Token::AssignDivide),
+   (":", Token::Colon),
+   (":=", Token::ColonAssign),
+   (";", Token::Semi),
+   ("<", Token::LT),
+   ("<<", Token::Lshift),
+   ("<<=", Token::AssignLshift),
+   ("<=", Token::LE),
+   ("=", Token::Assign),
+   ("==", Token::EQ),
+   (">", Token::GT),
+   (">=", Token::GE),
+   (">>", Token::Rshift),
+   (">>=", Token::AssignRshift),
+   ("@", Token::At),
+   ("@=", Token::AssignAt),
+   ("[", Token::Lbracket),
+   ("]", Token::Rbracket),
+   ("^", Token::BitXor),
+   ("^=", Token::AssignBitXor),
+   ("`", Token::Backtick),
+   ("{", Token::Lbrace),
+   ("|", Token::BitOr),
+   ("|=", Token::AssignBitOr),
+   ("}", Token::Rbrace),
+   ("~", Token::BitNot),
+];
+
+#[cfg(test)]
+mod tests
 {
-   let mut symbols = HashMap::new();
-
-   symbols.insert("+", Token::Plus);
-   symbols.insert("-", Token::Minus);
-   symbols.insert("*", Token::Times);
-   symbols.insert("**", Token::Exponent);
-   symbols.insert("/", Token::Divide);
-   symbols.insert("//", Token::DivideFloor);
-   symbols.insert("%", Token::Mod);
-   symbols.insert("@", Token::At);
-   symbols.insert("<<", Token::Lshift);
-   symbols.insert(">>", Token::Rshift);
-   symbols.insert("&", Token::BitAnd);
-   symbols.insert("|", Token::BitOr);
-   symbols.insert("^", Token::BitXor);
-   symbols.insert("~", Token::BitNot);
-   symbols.insert("<", Token::LT);
-   symbols.insert(">", Token::GT);
-   symbols.insert("<=", Token::LE);
-   symbols.insert(">=", Token::GE);
-   symbols.insert("==", Token::EQ);
-   symbols.insert("!=", Token::NE);
-   symbols.insert("(", Token::Lparen);
-   symbols.insert(")", Token::Rparen);
-   symbols.insert("[", Token::Lbracket);
-   symbols.insert("]", Token::Rbracket);
-   symbols.insert("{", Token::Lbrace);
-   symbols.insert("}", Token::Rbrace);
-   symbols.insert(",", Token::Comma);
-   symbols.insert(":", Token::Colon);
-   symbols.insert(".", Token::Dot);
-   symbols.insert("...", Token::Ellipsis);
-   symbols.insert(";", Token::Semi);
-   symbols.insert("->", Token::Arrow);
-   symbols.insert("=", Token::Assign);
-   symbols.insert("+=", Token::AssignPlus);
-   symbols.insert("-=", Token::AssignMinus);
-   symbols.insert("*=", Token::AssignTimes);
-   symbols.insert("/=", Token::AssignDivide);
-   symbols.insert("//=", Token::AssignDivideFloor);
-   symbols.insert("%=", Token::AssignMod);
-   symbols.insert("@=", Token::AssignAt);
-   symbols.insert("&=", Token::AssignBitAnd);
-   symbols.insert("|=", Token::AssignBitOr);
-   symbols.insert("^=", Token::AssignBitXor);
-   symbols.insert(">>=", Token::AssignRshift);
-   symbols.insert("<<=", Token::AssignLshift);
-   symbols.insert("**=", Token::AssignExponent);
-
-   symbols
+   use super::{Token, TokenCategory, NumericInfo, NumericValue, is_keyword,
+      keyword_lookup, symbol_lookup, KEYWORDS_PY2, KEYWORDS_PY3, SYMBOLS};
+   use options::PythonVersion;
+   use lexer::Lexer;
+   use std::collections::HashSet;
+
+   #[test]
+   fn test_classification()
+   {
+      let keyword = Token::Def;
+      let operator = Token::AssignExponent;
+      let comparison = Token::LE;
+      let delimiter = Token::Lbracket;
+      let literal = Token::Float("1.5".to_owned());
+      let identifier = Token::Identifier("x".to_owned());
+
+      assert!(keyword.is_keyword());
+      assert!(!keyword.is_operator());
+      assert!(operator.is_operator());
+      assert!(comparison.is_operator());
+      assert!(!operator.is_delimiter());
+      assert!(delimiter.is_delimiter());
+      assert!(!delimiter.is_literal());
+      assert!(literal.is_literal());
+      assert!(Token::Bytes(vec![]).is_literal());
+      assert!(!literal.is_keyword());
+      assert!(!identifier.is_keyword());
+      assert!(!identifier.is_operator());
+      assert!(!identifier.is_delimiter());
+      assert!(!identifier.is_literal());
+      assert!(!Token::Newline.is_delimiter());
+      assert!(Token::At.is_at());
+      assert!(!Token::AssignAt.is_at());
+   }
+
+   #[test]
+   fn test_lexemes()
+   {
+      // every token without contents has a lexeme that lexes back to it
+      for &(lexeme, ref token) in KEYWORDS_PY2.iter()
+      {
+         assert_eq!(token.clone().lexeme(), lexeme);
+         assert_eq!(&keyword_lookup(lexeme, PythonVersion::Python2), token);
+      }
+      for &(lexeme, ref token) in KEYWORDS_PY3.iter()
+      {
+         assert_eq!(token.clone().lexeme(), lexeme);
+         assert_eq!(&keyword_lookup(lexeme, PythonVersion::Python3), token);
+         assert!(is_keyword(lexeme));
+      }
+      for &(lexeme, ref token) in SYMBOLS.iter()
+      {
+         assert_eq!(token.clone().lexeme(), lexeme);
+         assert!(token.is_operator() || token.is_delimiter());
+         assert_eq!(symbol_lookup(lexeme).as_ref(), Ok(token));
+      }
+      for token in &[Token::Newline, Token::NL, Token::Indent, Token::Dedent]
+      {
+         assert_eq!(token.category(), TokenCategory::Layout);
+         assert!(!token.clone().lexeme().is_empty());
+      }
+   }
+
+   #[test]
+   fn test_tables_sorted()
+   {
+      // the lookups binary search the tables by spelling
+      for table in &[KEYWORDS_PY2, KEYWORDS_PY3, SYMBOLS]
+      {
+         assert!(table.windows(2).all(|pair| pair[0].0 < pair[1].0));
+      }
+   }
+
+   #[test]
+   fn test_keyword_str()
+   {
+      for &(spelling, ref token) in KEYWORDS_PY2.iter().chain(KEYWORDS_PY3)
+      {
+         assert_eq!(token.keyword_str(), Some(spelling));
+      }
+      assert_eq!(Token::Def.keyword_str(), Some("def"));
+      assert_eq!(Token::Plus.keyword_str(), None);
+      assert_eq!(Token::Identifier("def".to_owned()).keyword_str(), None);
+   }
+
+   #[test]
+   fn test_as_str_value()
+   {
+      let token = Token::Identifier("spam".to_owned());
+      assert_eq!(token.as_str_value(), Some("spam"));
+      assert_eq!(token, Token::Identifier("spam".to_owned()));
+      assert_eq!(Token::String("a b".to_owned()).as_str_value(), Some("a b"));
+      assert_eq!(Token::HexInteger("0xff".to_owned()).as_str_value(), Some("0xff"));
+      assert_eq!(Token::Plus.as_str_value(), None);
+      assert_eq!(Token::Comment("# c".to_owned()).as_str_value(), None);
+   }
+
+   #[test]
+   fn test_is_keyword()
+   {
+      assert!(is_keyword("def"));
+      assert!(is_keyword("None"));
+      assert!(!is_keyword("defdel"));
+      assert!(!is_keyword("match"));
+      assert!(!is_keyword("none"));
+      assert!(!is_keyword("print"));
+   }
+
+   #[test]
+   fn test_keyword_versions()
+   {
+      let cases = vec![
+         ("print", Token::Print, Token::Identifier("print".to_owned())),
+         ("exec", Token::Exec, Token::Identifier("exec".to_owned())),
+         ("nonlocal", Token::Identifier("nonlocal".to_owned()), Token::Nonlocal),
+         ("True", Token::Identifier("True".to_owned()), Token::True),
+         ("False", Token::Identifier("False".to_owned()), Token::False),
+         ("None", Token::Identifier("None".to_owned()), Token::None),
+         ("def", Token::Def, Token::Def),
+         ("with", Token::With, Token::With),
+      ];
+      for (spelling, py2, py3) in cases
+      {
+         assert_eq!(keyword_lookup(spelling, PythonVersion::Python2), py2);
+         assert_eq!(keyword_lookup(spelling, PythonVersion::Python3), py3);
+      }
+   }
+
+   #[test]
+   fn test_category()
+   {
+      assert_eq!(Token::While.category(), TokenCategory::Keyword);
+      assert_eq!(Token::Identifier("x".to_owned()).category(),
+         TokenCategory::Identifier);
+      assert_eq!(Token::Imaginary("1j".to_owned()).category(),
+         TokenCategory::NumericLiteral);
+      assert_eq!(Token::Bytes(vec![]).category(), TokenCategory::StringLiteral);
+      assert_eq!(Token::Rshift.category(), TokenCategory::Operator);
+      assert_eq!(Token::Arrow.category(), TokenCategory::Delimiter);
+      assert_eq!(Token::Dedent.category(), TokenCategory::Layout);
+      assert_eq!(Token::NL.category(), TokenCategory::Layout);
+   }
+
+   #[test]
+   fn test_augmented_assign()
+   {
+      let operators = [Token::Plus, Token::Minus, Token::Times,
+         Token::Exponent, Token::Divide, Token::DivideFloor, Token::BitAnd,
+         Token::BitOr, Token::BitXor, Token::Mod, Token::At, Token::Lshift,
+         Token::Rshift];
+      for op in operators.iter()
+      {
+         let augmented = op.with_equal();
+         assert!(augmented.is_augmented_assign());
+         assert!(!op.is_augmented_assign());
+         assert_eq!(augmented.base_operator().as_ref(), Some(op));
+      }
+
+      assert_eq!(Token::AssignAt.base_operator(), Some(Token::At));
+      assert_eq!(Token::AssignExponent.base_operator(), Some(Token::Exponent));
+      assert_eq!(Token::AssignRshift.base_operator(), Some(Token::Rshift));
+      assert_eq!(Token::AssignLshift.base_operator(), Some(Token::Lshift));
+      assert!(!Token::Assign.is_augmented_assign());
+      assert!(!Token::EQ.is_augmented_assign());
+      assert!(!Token::LE.is_augmented_assign());
+      assert_eq!(Token::GE.base_operator(), None);
+   }
+
+   #[test]
+   fn test_pygments_token_type()
+   {
+      assert_eq!(Token::Def.pygments_token_type(), "Token.Keyword");
+      assert_eq!(Token::None.pygments_token_type(), "Token.Keyword.Constant");
+      assert_eq!(Token::Not.pygments_token_type(), "Token.Operator.Word");
+      assert_eq!(Token::Identifier("x".to_owned()).pygments_token_type(),
+         "Token.Name");
+      assert_eq!(Token::DecInteger("1".to_owned()).pygments_token_type(),
+         "Token.Literal.Number.Integer");
+      assert_eq!(Token::HexInteger("0x1".to_owned()).pygments_token_type(),
+         "Token.Literal.Number.Hex");
+      assert_eq!(Token::Float("1.".to_owned()).pygments_token_type(),
+         "Token.Literal.Number.Float");
+      assert_eq!(Token::String("s".to_owned()).pygments_token_type(),
+         "Token.Literal.String");
+      assert_eq!(Token::Bytes(vec![]).pygments_token_type(),
+         "Token.Literal.String");
+      assert_eq!(Token::AssignPlus.pygments_token_type(), "Token.Operator");
+      assert_eq!(Token::Lparen.pygments_token_type(), "Token.Punctuation");
+      assert_eq!(Token::Newline.pygments_token_type(), "Token.Text");
+   }
+
+   #[test]
+   fn test_base()
+   {
+      let dec = Token::DecInteger("1234".to_owned());
+      let hex = Token::HexInteger("0xdeadBEEF".to_owned());
+      let oct = Token::OctInteger("0O777".to_owned());
+      let bin = Token::BinInteger("0b1011".to_owned());
+      assert_eq!(dec.base(), Some(10));
+      assert_eq!(hex.base(), Some(16));
+      assert_eq!(oct.base(), Some(8));
+      assert_eq!(bin.base(), Some(2));
+      assert_eq!(dec.digits_without_prefix(), Some("1234"));
+      assert_eq!(hex.digits_without_prefix(), Some("deadBEEF"));
+      assert_eq!(oct.digits_without_prefix(), Some("777"));
+      assert_eq!(bin.digits_without_prefix(), Some("1011"));
+      assert_eq!(Token::Float("1.5".to_owned()).base(), None);
+      assert_eq!(Token::Imaginary("2j".to_owned()).digits_without_prefix(),
+         None);
+      assert_eq!(Token::Identifier("x".to_owned()).base(), None);
+   }
+
+   #[test]
+   fn test_numeric_info()
+   {
+      assert_eq!(Token::HexInteger("0xFF".to_owned()).numeric_info(),
+         Some(NumericInfo{value: Some(NumericValue::Integer(255)),
+            base: 16, is_float: false, is_imaginary: false, source_len: 4}));
+      assert_eq!(Token::Float("2.5e3".to_owned()).numeric_info(),
+         Some(NumericInfo{value: Some(NumericValue::Float(2500.0)),
+            base: 10, is_float: true, is_imaginary: false, source_len: 5}));
+      assert_eq!(Token::Imaginary("1.5j".to_owned()).numeric_info(),
+         Some(NumericInfo{value: Some(NumericValue::Float(1.5)),
+            base: 10, is_float: true, is_imaginary: true, source_len: 4}));
+      assert_eq!(Token::Imaginary("037j".to_owned()).numeric_info(),
+         Some(NumericInfo{value: Some(NumericValue::Float(37.0)),
+            base: 10, is_float: false, is_imaginary: true, source_len: 4}));
+      assert_eq!(Token::Imaginary("1_000.5e-2j".to_owned()).numeric_info(),
+         Some(NumericInfo{value: Some(NumericValue::Float(10.005)),
+            base: 10, is_float: true, is_imaginary: true, source_len: 11}));
+      assert_eq!(Token::DecInteger("1".repeat(40)).numeric_info().unwrap().value,
+         None);
+      assert_eq!(Token::OctInteger("0o17L".to_owned()).numeric_info().unwrap().value,
+         Some(NumericValue::Integer(15)));
+      assert_eq!(Token::HexInteger("0x_ff_ff".to_owned()).numeric_info().unwrap().value,
+         Some(NumericValue::Integer(0xffff)));
+      assert_eq!(Token::Float("1_0.5e1_0".to_owned()).numeric_info().unwrap().value,
+         Some(NumericValue::Float(10.5e10)));
+      assert_eq!(Token::Identifier("x".to_owned()).numeric_info(), None);
+   }
+
+   #[test]
+   fn test_source_text()
+   {
+      let line = "x = r'\\d' +   077e010 if not y else b\"\\x41\"  # c\n";
+      let mut l = Lexer::new(line);
+      let mut rebuilt = String::new();
+      let mut previous_end = 0;
+      while let Some((_, token, span)) = l.next_spanned()
+      {
+         rebuilt.push_str(&line[previous_end..span.start]);
+         rebuilt.push_str(token.unwrap().source_text(line, span));
+         previous_end = span.end;
+      }
+      rebuilt.push_str(&line[previous_end..]);
+      assert_eq!(rebuilt, line);
+
+      let mut l = Lexer::new(line);
+      l.next();
+      l.next();
+      let (_, token, span) = l.next_spanned().unwrap();
+      assert_eq!(token.unwrap().source_text(line, span), "r'\\d'");
+   }
+
+   #[test]
+   fn test_hash()
+   {
+      let mut seen = HashSet::new();
+      for (_, token) in Lexer::new("x = x + 1.5 if x else 'x'")
+      {
+         seen.insert(token.unwrap());
+      }
+      assert_eq!(seen.len(), 8);
+      assert!(seen.contains(&Token::Identifier("x".to_owned())));
+      assert!(seen.contains(&Token::String("x".to_owned())));
+      assert!(seen.contains(&Token::Float("1.5".to_owned())));
+      assert!(seen.contains(&Token::Assign));
+      assert!(!seen.contains(&Token::Identifier("y".to_owned())));
+      assert!(!seen.contains(&Token::Float("1.50".to_owned())));
+   }
+
+   #[test]
+   fn test_discriminant()
+   {
+      let tokens = vec![
+         Token::Newline, Token::Indent, Token::Dedent, Token::False,
+         Token::None, Token::True, Token::And, Token::As, Token::Assert,
+         Token::Break, Token::Class, Token::Continue, Token::Def, Token::Del,
+         Token::Elif, Token::Else, Token::Except, Token::Finally, Token::For,
+         Token::From, Token::Global, Token::If, Token::Import, Token::In,
+         Token::Is, Token::Lambda, Token::Nonlocal, Token::Not, Token::Or,
+         Token::Pass, Token::Raise, Token::Return, Token::Try, Token::While,
+         Token::With, Token::Yield, Token::Plus, Token::Minus, Token::Times,
+         Token::Exponent, Token::Divide, Token::DivideFloor, Token::Mod,
+         Token::At, Token::Lshift, Token::Rshift, Token::BitAnd, Token::BitOr,
+         Token::BitXor, Token::BitNot, Token::LT, Token::GT, Token::LE,
+         Token::GE, Token::EQ, Token::NE, Token::Lparen, Token::Rparen,
+         Token::Lbracket, Token::Rbracket, Token::Lbrace, Token::Rbrace,
+         Token::Comma, Token::Colon, Token::Dot, Token::Ellipsis, Token::Semi,
+         Token::Arrow, Token::Assign, Token::ColonAssign, Token::AssignPlus,
+         Token::AssignMinus, Token::AssignTimes, Token::AssignDivide,
+         Token::AssignDivideFloor, Token::AssignMod, Token::AssignAt,
+         Token::AssignBitAnd, Token::AssignBitOr, Token::AssignBitXor,
+         Token::AssignRshift, Token::AssignLshift, Token::AssignExponent,
+         Token::Identifier(String::new()), Token::String(String::new()),
+         Token::FormatString(String::new()), Token::Bytes(vec![]),
+         Token::DecInteger(String::new()), Token::BinInteger(String::new()),
+         Token::OctInteger(String::new()), Token::HexInteger(String::new()),
+         Token::Float(String::new()), Token::Imaginary(String::new()),
+         Token::Whitespace(String::new()), Token::Comment(String::new()),
+         Token::Backtick, Token::Print, Token::Exec, Token::NL,
+         Token::TypeComment(String::new())];
+      let discriminants: HashSet<u32> =
+         tokens.iter().map(|t| t.discriminant()).collect();
+      assert_eq!(discriminants.len(), tokens.len());
+      assert_eq!(Token::Newline.discriminant(), 0);
+      assert_eq!(Token::Identifier("x".to_owned()).discriminant(),
+         Token::Identifier("y".to_owned()).discriminant());
+   }
+
+   #[test]
+   fn test_eq_str()
+   {
+      let token = Token::Identifier("self".to_owned());
+      assert!(token == "self");
+      assert!(token != "other");
+      assert!(token == *"self");
+      assert!(Token::String("self".to_owned()) == "self");
+      assert!(Token::FormatString("self".to_owned()) != "self");
+      assert!(Token::Comment("self".to_owned()) != "self");
+      assert!(Token::Pass != "pass");
+   }
+
+   #[cfg(feature = "bigint")]
+   #[test]
+   fn test_as_biguint()
+   {
+      use num_bigint::BigUint;
+
+      let large = "79228162514264337593543950336";
+      let expected = BigUint::parse_bytes(large.as_bytes(), 10).unwrap();
+      assert_eq!(Token::DecInteger(large.to_owned()).as_biguint(),
+         Some(expected.clone()));
+      assert_eq!(Token::HexInteger("0x1000000000000000000000000".to_owned())
+         .as_biguint(), Some(expected));
+      assert_eq!(Token::HexInteger("0xdeadbeef".to_owned()).as_biguint(),
+         Some(BigUint::from(0xdeadbeefu32)));
+      assert_eq!(Token::BinInteger("0b101".to_owned()).as_biguint(),
+         Some(BigUint::from(5u32)));
+      let tokens = Lexer::tokens("340282366920938463463374607431768211456 7");
+      assert_eq!(tokens[0].1.as_ref().unwrap().as_biguint().map(|n| n.to_string()),
+         Some("340282366920938463463374607431768211456".to_owned()));
+      assert_eq!(tokens[1].1.as_ref().unwrap().as_biguint(),
+         Some(BigUint::from(7u32)));
+      assert_eq!(Token::Float("1.5".to_owned()).as_biguint(), None);
+      assert_eq!(Token::Plus.as_biguint(), None);
+   }
 }